@@ -1,4 +1,4 @@
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 
 use anyhow::{anyhow, Context};
 use argon2::{
@@ -7,10 +7,13 @@ use argon2::{
     Argon2, PasswordHasher, PasswordVerifier,
 };
 use async_trait::async_trait;
-use chrono::{Duration, Utc};
+use chrono::{DateTime, Duration, Utc};
+use hmac::{Hmac, Mac};
 use juniper::ID;
+use rand::RngCore;
+use sha1::Sha1;
 use tabby_db::{DbConn, InvitationDAO};
-use tokio::task::JoinHandle;
+use tokio::{sync::RwLock, task::JoinHandle};
 use tracing::warn;
 
 use super::{graphql_pagination_to_filter, AsID, AsRowid};
@@ -18,10 +21,10 @@ use crate::{
     oauth,
     schema::{
         auth::{
-            generate_jwt, generate_refresh_token, validate_jwt, AuthenticationService, Invitation,
-            JWTPayload, OAuthCredential, OAuthError, OAuthProvider, OAuthResponse,
-            RefreshTokenResponse, RegisterResponse, RequestInvitationInput, TokenAuthResponse,
-            UpdateOAuthCredentialInput, User,
+            generate_jwt, generate_refresh_token, jwt_token_secret, validate_jwt,
+            AuthenticationService, Invitation, JWTPayload, OAuthCredential, OAuthError,
+            OAuthProvider, OAuthResponse, RefreshTokenResponse, RegisterResponse,
+            RequestInvitationInput, TokenAuthResponse, UpdateOAuthCredentialInput, User,
         },
         email::EmailService,
         license::{IsLicenseValid, LicenseService},
@@ -30,11 +33,56 @@ use crate::{
     },
 };
 
+/// A cached `.well-known/openid-configuration` document, keyed by issuer URL and refreshed
+/// every [OIDC_DISCOVERY_TTL].
+#[derive(Clone, Debug, serde::Deserialize)]
+struct OidcDiscoveryDocument {
+    authorization_endpoint: String,
+    token_endpoint: String,
+    userinfo_endpoint: Option<String>,
+    jwks_uri: String,
+}
+
+const OIDC_DISCOVERY_TTL: Duration = Duration::hours(1);
+
+#[derive(Default)]
+struct OidcDiscoveryCache {
+    entries: RwLock<HashMap<String, (DateTime<Utc>, OidcDiscoveryDocument)>>,
+}
+
+impl OidcDiscoveryCache {
+    async fn get_or_fetch(&self, issuer: &str) -> Result<OidcDiscoveryDocument> {
+        if let Some((fetched_at, doc)) = self.entries.read().await.get(issuer) {
+            if Utc::now().signed_duration_since(*fetched_at) < OIDC_DISCOVERY_TTL {
+                return Ok(doc.clone());
+            }
+        }
+
+        let url = format!(
+            "{}/.well-known/openid-configuration",
+            issuer.trim_end_matches('/')
+        );
+        let doc = reqwest::get(url)
+            .await
+            .map_err(|e| anyhow!("Failed to fetch OIDC discovery document: {e}"))?
+            .json::<OidcDiscoveryDocument>()
+            .await
+            .map_err(|e| anyhow!("Malformed OIDC discovery document: {e}"))?;
+
+        self.entries
+            .write()
+            .await
+            .insert(issuer.to_owned(), (Utc::now(), doc.clone()));
+        Ok(doc)
+    }
+}
+
 #[derive(Clone)]
 struct AuthenticationServiceImpl {
     db: DbConn,
     mail: Arc<dyn EmailService>,
     license: Arc<dyn LicenseService>,
+    oidc_discovery: Arc<OidcDiscoveryCache>,
 }
 
 pub fn new_authentication_service(
@@ -42,7 +90,12 @@ pub fn new_authentication_service(
     mail: Arc<dyn EmailService>,
     license: Arc<dyn LicenseService>,
 ) -> impl AuthenticationService {
-    AuthenticationServiceImpl { db, mail, license }
+    AuthenticationServiceImpl {
+        db,
+        mail,
+        license,
+        oidc_discovery: Arc::new(OidcDiscoveryCache::default()),
+    }
 }
 
 #[async_trait]
@@ -52,6 +105,7 @@ impl AuthenticationService for AuthenticationServiceImpl {
         email: String,
         password: String,
         invitation_code: Option<String>,
+        device: DeviceContext,
     ) -> Result<RegisterResponse> {
         let is_admin_initialized = self.is_admin_initialized().await?;
         let invitation =
@@ -62,10 +116,16 @@ impl AuthenticationService for AuthenticationServiceImpl {
             return Err(anyhow!("Email is already registered").into());
         }
 
-        let Ok(pwd_hash) = password_hash(&password) else {
+        let argon2_params = self.read_argon2_params().await?;
+        let Ok(pwd_hash) = password_hash_with_params(&password, argon2_params) else {
             return Err(anyhow!("Unknown error").into());
         };
 
+        // Invitations and the bootstrap admin account already prove ownership of the email
+        // (the invite was mailed to it, or there's no one else around to have registered it),
+        // so only a bare self-signup needs to go through email verification.
+        let auto_verified = invitation.is_some() || !is_admin_initialized;
+
         let id = if let Some(invitation) = invitation {
             self.db
                 .create_user_with_invitation(
@@ -81,10 +141,21 @@ impl AuthenticationService for AuthenticationServiceImpl {
                 .await?
         };
 
+        if auto_verified {
+            self.db.mark_user_verified(id).await?;
+        } else {
+            let code = self.db.create_email_verification(id).await?;
+            self.mail
+                .send_verification_email(email.clone(), code)
+                .await?;
+        }
+
         let user = self.db.get_user(id).await?.unwrap();
 
         let refresh_token = generate_refresh_token();
-        self.db.create_refresh_token(id, &refresh_token).await?;
+        self.db
+            .create_refresh_token_with_device(id, &refresh_token, &device)
+            .await?;
 
         let Ok(access_token) = generate_jwt(JWTPayload::new(user.email.clone(), user.is_admin))
         else {
@@ -105,7 +176,27 @@ impl AuthenticationService for AuthenticationServiceImpl {
         Ok(is_email_configured && !domain_list.is_empty())
     }
 
-    async fn request_password_reset_email(&self, email: String) -> Result<Option<JoinHandle<()>>> {
+    /// Requests a password reset email, throttled per-email and per-IP so this can't be used
+    /// to email-bomb an address or to probe whether it has an account: every rejection -- no
+    /// such user, inactive user, or over the rate limit -- comes back as the same `Ok(None)`.
+    async fn request_password_reset_email(
+        &self,
+        email: String,
+        ip: Option<String>,
+    ) -> Result<Option<JoinHandle<()>>> {
+        let limits = self.read_rate_limit_settings().await?;
+        let window = Duration::minutes(limits.reset_window_minutes);
+        let recent = self
+            .db
+            .count_recent_password_reset_requests(&email, ip.as_deref(), window)
+            .await?;
+        self.db
+            .record_password_reset_request(&email, ip.as_deref())
+            .await?;
+        if recent >= limits.reset_max_attempts as i64 {
+            return Ok(None);
+        }
+
         let user = self.get_user_by_email(&email).await.ok();
 
         let Some(user @ User { active: true, .. }) = user else {
@@ -113,15 +204,6 @@ impl AuthenticationService for AuthenticationServiceImpl {
         };
 
         let id = user.id.as_rowid()?;
-        let existing = self.db.get_password_reset_by_user_id(id as i64).await?;
-        if let Some(existing) = existing {
-            if Utc::now().signed_duration_since(*existing.created_at) < Duration::minutes(5) {
-                return Err(anyhow!(
-                    "A password reset has been requested recently, please try again later"
-                )
-                .into());
-            }
-        }
         let code = self.db.create_password_reset(id as i64).await?;
         let handle = self
             .mail
@@ -130,33 +212,145 @@ impl AuthenticationService for AuthenticationServiceImpl {
         Ok(Some(handle))
     }
 
-    async fn password_reset(&self, code: &str, password: &str) -> Result<()> {
-        let password_encrypted = password_hash(password).map_err(|_| anyhow!("Unknown error"))?;
+    /// Redeems a password reset code, locking the submitting IP out (with an exponentially
+    /// growing delay, see [lockout_minutes]) after repeated wrong codes so it can't be brute
+    /// forced. When no IP is available, falls back to the account the code was issued to
+    /// (rather than the code itself, which is different on every guess and so would never
+    /// accumulate enough failures to lock anything out) so callers that can't supply an IP
+    /// still get a real throttle instead of one that only looks like it works.
+    async fn password_reset(&self, code: &str, password: &str, ip: Option<String>) -> Result<()> {
+        let limits = self.read_rate_limit_settings().await?;
+        let key = match &ip {
+            Some(ip) => ip.clone(),
+            None => match self.db.get_password_reset_by_code(code).await? {
+                Some(reset) => format!("user:{}", reset.user_id),
+                None => code.to_string(),
+            },
+        };
+        let key = key.as_str();
+        let window = Duration::minutes(limits.lockout_window_minutes);
+        let failures = self.db.count_recent_failed_password_resets(key, window).await?;
+        if lockout_minutes(failures as u32, limits.lockout_threshold, limits.lockout_base_minutes) > 0 {
+            return Err(anyhow!("Too many attempts, please try again later").into());
+        }
+
+        let user_id = match self.db.verify_password_reset(code).await {
+            Ok(user_id) => user_id,
+            Err(e) => {
+                self.db.record_failed_password_reset(key).await?;
+                return Err(e.into());
+            }
+        };
+
+        let argon2_params = self.read_argon2_params().await?;
+        let password_encrypted = password_hash_with_params(password, argon2_params)
+            .map_err(|_| anyhow!("Unknown error"))?;
 
-        let user_id = self.db.verify_password_reset(code).await?;
         self.db.delete_password_reset_by_user_id(user_id).await?;
         self.db
             .update_user_password(user_id as i32, password_encrypted)
             .await?;
+        self.db.clear_failed_password_resets(key).await?;
         Ok(())
     }
 
-    async fn token_auth(&self, email: String, password: String) -> Result<TokenAuthResponse> {
-        let Some(user) = self.db.get_user_by_email(&email).await? else {
-            return Err(anyhow!("User not found").into());
+    async fn token_auth(
+        &self,
+        email: String,
+        password: String,
+        device: DeviceContext,
+    ) -> Result<TokenAuthResponse> {
+        let existing = self.db.get_user_by_email(&email).await?;
+        // An empty password must never reach `authenticate_ldap`: a simple bind with one is
+        // an RFC 4513 unauthenticated bind, which most directories accept regardless of DN.
+        if password.is_empty() {
+            self.db.record_failed_login(&email).await?;
+            return Err(anyhow!("Password is not valid").into());
+        }
+        let ldap = self.db.read_ldap_setting().await?.filter(|s| s.enabled);
+
+        let limits = self.read_rate_limit_settings().await?;
+        let window = Duration::minutes(limits.lockout_window_minutes);
+        let failures = self.db.count_recent_failed_logins(&email, window).await?;
+        if lockout_minutes(failures as u32, limits.lockout_threshold, limits.lockout_base_minutes) > 0 {
+            return Err(anyhow!("Too many failed attempts, please try again later").into());
+        }
+
+        // Local accounts (non-empty password hash) always verify against Argon2. Empty-password
+        // accounts can't log in locally (see `get_or_create_oauth_user`), so they only succeed
+        // through LDAP when it's configured; a user unknown to Tabby is located-or-provisioned
+        // on a successful LDAP bind, the same way OAuth provisions on first login.
+        let user = match existing {
+            Some(user) if !user.password_encrypted.is_empty() => {
+                let argon2_params = self.read_argon2_params().await?;
+                match verify_password(&password, &user.password_encrypted, argon2_params) {
+                    PasswordVerifyOutcome::Invalid => {
+                        self.db.record_failed_login(&email).await?;
+                        return Err(anyhow!("Password is not valid").into());
+                    }
+                    PasswordVerifyOutcome::Valid => {}
+                    // Opportunistically upgrade legacy (e.g. bcrypt-imported) hashes, or ones
+                    // hashed under stale Argon2 cost parameters, now that we have the plaintext
+                    // in hand, rather than forcing every affected user through a reset.
+                    PasswordVerifyOutcome::ValidNeedsRehash => {
+                        if let Ok(rehashed) = password_hash_with_params(&password, argon2_params) {
+                            self.db.update_user_password(user.id, rehashed).await?;
+                        }
+                    }
+                }
+                user
+            }
+            Some(user) => {
+                let Some(ldap) = &ldap else {
+                    self.db.record_failed_login(&email).await?;
+                    return Err(anyhow!("Password is not valid").into());
+                };
+                if authenticate_ldap(ldap, &email, &password).await.is_err() {
+                    self.db.record_failed_login(&email).await?;
+                    return Err(anyhow!("Password is not valid").into());
+                }
+                user
+            }
+            None => {
+                let Some(ldap) = &ldap else {
+                    self.db.record_failed_login(&email).await?;
+                    return Err(anyhow!("User not found").into());
+                };
+                if authenticate_ldap(ldap, &email, &password).await.is_err() {
+                    self.db.record_failed_login(&email).await?;
+                    return Err(anyhow!("Password is not valid").into());
+                }
+                let (id, _) = get_or_create_oauth_user(&self.db, &email)
+                    .await
+                    .map_err(|e| anyhow!("Failed to provision LDAP user: {e}"))?;
+                self.db.get_user(id).await?.context("User doesn't exist")?
+            }
         };
 
+        self.db.clear_failed_logins(&email).await?;
+
         if !user.active {
             return Err(anyhow!("User is disabled").into());
         }
 
-        if !password_verify(&password, &user.password_encrypted) {
-            return Err(anyhow!("Password is not valid").into());
+        if !user.is_verified {
+            return Err(CoreError::EmailNotVerified);
+        }
+
+        if self.db.get_totp_credential(user.id).await?.is_some() {
+            // A correct password alone isn't enough: hand back a short-lived challenge the
+            // caller must redeem via `verify_totp` with the second factor.
+            let challenge = generate_totp_challenge_jwt(
+                &totp_challenge_signing_key(),
+                Totp2FAChallengePayload::new(user.email.clone()),
+            )
+            .map_err(|_| anyhow!("Unknown error"))?;
+            return Err(CoreError::TotpRequired(challenge));
         }
 
         let refresh_token = generate_refresh_token();
         self.db
-            .create_refresh_token(user.id, &refresh_token)
+            .create_refresh_token_with_device(user.id, &refresh_token, &device)
             .await?;
 
         let Ok(access_token) = generate_jwt(JWTPayload::new(user.email.clone(), user.is_admin))
@@ -184,6 +378,8 @@ impl AuthenticationService for AuthenticationServiceImpl {
         }
 
         let new_token = generate_refresh_token();
+        // Carries the device metadata (and bumps `last_used_at`) forward onto the new token
+        // row so the session list reflects the refresh as activity on the same session.
         self.db.replace_refresh_token(&token, &new_token).await?;
 
         // refresh token update is done, generate new access token based on user info
@@ -207,6 +403,18 @@ impl AuthenticationService for AuthenticationServiceImpl {
         Ok(())
     }
 
+    async fn delete_expired_device_codes(&self) -> Result<()> {
+        self.db.delete_expired_device_codes().await?;
+        Ok(())
+    }
+
+    /// Purges password-reset-request and failed-attempt counters outside every configured
+    /// rate-limit window, analogous to [Self::delete_expired_password_resets].
+    async fn delete_expired_rate_limit_counters(&self) -> Result<()> {
+        self.db.delete_expired_rate_limit_counters().await?;
+        Ok(())
+    }
+
     async fn verify_access_token(&self, access_token: &str) -> Result<JWTPayload> {
         let claims = validate_jwt(access_token).map_err(anyhow::Error::new)?;
         Ok(claims)
@@ -316,14 +524,19 @@ impl AuthenticationService for AuthenticationServiceImpl {
         &self,
         code: String,
         provider: OAuthProvider,
+        device: DeviceContext,
     ) -> std::result::Result<OAuthResponse, OAuthError> {
-        let client = oauth::new_oauth_client(provider, Arc::new(self.clone()));
-        let email = client.fetch_user_email(code).await?;
+        let email = if let OAuthProvider::Oidc = provider {
+            self.fetch_oidc_user_email(code).await?
+        } else {
+            let client = oauth::new_oauth_client(provider, Arc::new(self.clone()));
+            client.fetch_user_email(code).await?
+        };
         let (user_id, is_admin) = get_or_create_oauth_user(&self.db, &email).await?;
 
         let refresh_token = generate_refresh_token();
         self.db
-            .create_refresh_token(user_id, &refresh_token)
+            .create_refresh_token_with_device(user_id, &refresh_token, &device)
             .await?;
 
         let access_token = generate_jwt(JWTPayload::new(email.clone(), is_admin))
@@ -351,6 +564,11 @@ impl AuthenticationService for AuthenticationServiceImpl {
                 .read_google_oauth_credential()
                 .await?
                 .map(|val| val.into())),
+            OAuthProvider::Oidc => Ok(self
+                .db
+                .read_oidc_credential()
+                .await?
+                .map(|val| val.into())),
         }
     }
 
@@ -359,6 +577,7 @@ impl AuthenticationService for AuthenticationServiceImpl {
         let url = match provider {
             OAuthProvider::Github => external_url + "/oauth/callback/github",
             OAuthProvider::Google => external_url + "/oauth/callback/google",
+            OAuthProvider::Oidc => external_url + "/oauth/callback/oidc",
         };
         Ok(url)
     }
@@ -373,6 +592,22 @@ impl AuthenticationService for AuthenticationServiceImpl {
                 .db
                 .update_google_oauth_credential(&input.client_id, input.client_secret.as_deref())
                 .await?),
+            OAuthProvider::Oidc => {
+                let issuer = input
+                    .issuer
+                    .ok_or_else(|| anyhow!("Issuer is required for OIDC credentials"))?;
+                // Eagerly validate the issuer is reachable and speaks discovery, so a typo in
+                // the admin UI is caught immediately instead of surfacing as a login failure.
+                self.oidc_discovery.get_or_fetch(&issuer).await?;
+                Ok(self
+                    .db
+                    .update_oidc_credential(
+                        &issuer,
+                        &input.client_id,
+                        input.client_secret.as_deref(),
+                    )
+                    .await?)
+            }
         }
     }
 
@@ -380,6 +615,7 @@ impl AuthenticationService for AuthenticationServiceImpl {
         let ret = match provider {
             OAuthProvider::Github => self.db.delete_github_oauth_credential().await,
             OAuthProvider::Google => self.db.delete_google_oauth_credential().await,
+            OAuthProvider::Oidc => self.db.delete_oidc_credential().await,
         };
         Ok(ret?)
     }
@@ -394,6 +630,768 @@ impl AuthenticationService for AuthenticationServiceImpl {
     }
 }
 
+/// Bind credentials and search parameters for authenticating against an external directory.
+///
+/// `user_filter` is an RFC 4515 filter template with a single `{}` placeholder substituted
+/// with the login email, e.g. `(mail={})` or `(&(objectClass=person)(uid={}))`.
+#[derive(Clone, Debug)]
+pub struct LdapSetting {
+    pub enabled: bool,
+    pub url: String,
+    pub bind_dn: String,
+    pub bind_password: String,
+    pub base_dn: String,
+    pub user_filter: String,
+    pub use_starttls: bool,
+}
+
+/// Authenticates `email`/`password` against the directory described by `setting`.
+///
+/// Performs a search bind with the service account to locate the user's DN, then rebinds
+/// as that DN with the supplied password; a successful rebind is the proof of a correct
+/// password, so no password ever needs to be compared locally.
+async fn authenticate_ldap(setting: &LdapSetting, email: &str, password: &str) -> Result<()> {
+    use ldap3::{LdapConnAsync, Scope, SearchEntry};
+
+    // A simple bind with an empty password is an RFC 4513 §5.1.2 "unauthenticated bind",
+    // which most directories report as a successful result regardless of the DN -- rejecting
+    // it here, rather than trusting callers not to send one, is what actually stops it.
+    if password.is_empty() {
+        return Err(anyhow!("Password is not valid").into());
+    }
+
+    let (conn, mut ldap) = LdapConnAsync::new(&setting.url)
+        .await
+        .map_err(|e| anyhow!("Failed to connect to LDAP server: {e}"))?;
+    ldap3::drive!(conn);
+
+    if setting.use_starttls {
+        ldap.starttls()
+            .await
+            .map_err(|e| anyhow!("LDAP StartTLS failed: {e}"))?;
+    }
+
+    ldap.simple_bind(&setting.bind_dn, &setting.bind_password)
+        .await
+        .and_then(|res| res.success())
+        .map_err(|e| anyhow!("LDAP service account bind failed: {e}"))?;
+
+    let filter = setting.user_filter.replace("{}", email);
+    let (entries, _) = ldap
+        .search(&setting.base_dn, Scope::Subtree, &filter, vec!["dn"])
+        .await
+        .and_then(|res| res.success())
+        .map_err(|e| anyhow!("LDAP user search failed: {e}"))?;
+    let entry = entries
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("No LDAP user matches {email}"))?;
+    let dn = SearchEntry::construct(entry).dn;
+
+    ldap.simple_bind(&dn, password)
+        .await
+        .and_then(|res| res.success())
+        .map_err(|_| anyhow!("Password is not valid"))?;
+
+    let _ = ldap.unbind().await;
+    Ok(())
+}
+
+/// Client-supplied and request-derived metadata recorded alongside a refresh token so a
+/// user can later see (and revoke) where they're logged in.
+#[derive(Clone, Debug, Default)]
+pub struct DeviceContext {
+    pub device_name: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+/// Active-session summary surfaced to the owning user; deliberately excludes the refresh
+/// token value itself.
+pub struct Session {
+    pub id: ID,
+    pub device_name: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: DateTime<Utc>,
+    pub is_current: bool,
+}
+
+/// Access level granted to a personal access token. `ReadOnly` is meant for editor
+/// extensions and CI that only ever call completion endpoints; `FullAccess` mirrors what a
+/// regular session can do.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PersonalAccessTokenScope {
+    ReadOnly,
+    FullAccess,
+}
+
+/// Personal access token metadata surfaced to the owning user. The raw secret is never
+/// stored and never appears here -- it's returned exactly once, at creation time, by
+/// [AuthenticationServiceImpl::create_personal_access_token].
+pub struct PersonalAccessToken {
+    pub id: ID,
+    pub name: String,
+    pub scope: PersonalAccessTokenScope,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+const PERSONAL_ACCESS_TOKEN_PREFIX: &str = "tabby_pat_";
+
+/// Generates the random secret half of a personal access token. Encoded with the same
+/// alphabet as TOTP secrets so it's safe to embed in a bearer header without escaping.
+fn generate_personal_access_token_secret() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    base32::encode(base32::Alphabet::Rfc4648 { padding: false }, &bytes)
+}
+
+impl AuthenticationServiceImpl {
+    async fn list_sessions(&self, user_id: i32, current: &str) -> Result<Vec<Session>> {
+        Ok(self
+            .db
+            .list_refresh_tokens(user_id)
+            .await?
+            .into_iter()
+            .map(|t| Session {
+                id: t.id.as_id(),
+                device_name: t.device_name,
+                ip: t.ip,
+                user_agent: t.user_agent,
+                created_at: *t.created_at,
+                last_used_at: *t.last_used_at,
+                is_current: t.token == current,
+            })
+            .collect())
+    }
+
+    async fn revoke_session(&self, id: &ID) -> Result<()> {
+        Ok(self.db.delete_refresh_token_by_id(id.as_rowid()?).await?)
+    }
+
+    async fn revoke_all_sessions_except(&self, user_id: i32, current: &str) -> Result<()> {
+        Ok(self
+            .db
+            .delete_refresh_tokens_except(user_id, current)
+            .await?)
+    }
+
+    async fn read_argon2_params(&self) -> Result<Argon2Params> {
+        Ok(self
+            .db
+            .read_argon2_setting()
+            .await?
+            .unwrap_or_default())
+    }
+
+    async fn update_argon2_params(&self, params: Argon2Params) -> Result<()> {
+        params.validate()?;
+        Ok(self.db.update_argon2_setting(&params).await?)
+    }
+
+    async fn read_rate_limit_settings(&self) -> Result<RateLimitSettings> {
+        Ok(self.db.read_rate_limit_setting().await?.unwrap_or_default())
+    }
+
+    async fn update_rate_limit_settings(&self, settings: RateLimitSettings) -> Result<()> {
+        Ok(self.db.update_rate_limit_setting(&settings).await?)
+    }
+
+    /// Validates a verification code minted by [Self::register] and activates the account.
+    async fn verify_email(&self, code: &str) -> Result<()> {
+        let user_id = self.db.verify_email_verification(code).await?;
+        self.db.delete_email_verification_by_user_id(user_id).await?;
+        self.db.mark_user_verified(user_id).await?;
+        Ok(())
+    }
+
+    /// Resends the verification email, throttled the same way as
+    /// [AuthenticationService::request_password_reset_email].
+    async fn resend_verification_email(&self, email: String) -> Result<()> {
+        let user = self.get_user_by_email(&email).await?;
+        if user.is_verified {
+            return Ok(());
+        }
+
+        let id = user.id.as_rowid()?;
+        if let Some(existing) = self.db.get_email_verification_by_user_id(id as i64).await? {
+            if Utc::now().signed_duration_since(*existing.created_at) < Duration::minutes(5) {
+                return Err(anyhow!(
+                    "A verification email has been sent recently, please try again later"
+                )
+                .into());
+            }
+        }
+        let code = self.db.create_email_verification(id as i64).await?;
+        self.mail.send_verification_email(user.email, code).await?;
+        Ok(())
+    }
+
+    /// Starts an RFC 8628 device authorization flow: a random `device_code` (kept secret,
+    /// polled by the client) is paired with a short, human-typable `user_code` shown to the
+    /// user on a second device.
+    async fn request_device_code(&self) -> Result<DeviceAuthorizationResponse> {
+        let device_code = generate_refresh_token();
+        let user_code = generate_user_code();
+        self.db
+            .create_device_token(&device_code, &user_code)
+            .await?;
+
+        Ok(DeviceAuthorizationResponse {
+            device_code,
+            user_code,
+            verification_uri: self.db.read_network_setting().await?.external_url
+                + "/device-authorize",
+            expires_in: DEVICE_CODE_TTL.num_seconds(),
+            interval: DEVICE_CODE_POLL_INTERVAL.num_seconds(),
+        })
+    }
+
+    /// Binds a pending device authorization to `user_id` once they've confirmed `user_code`
+    /// while authenticated in a regular browser session.
+    async fn approve_device_code(&self, user_code: &str, user_id: i32) -> Result<()> {
+        Ok(self
+            .db
+            .set_device_token_user(user_code, user_id)
+            .await?)
+    }
+
+    /// Exchanges an approved `device_code` for tokens, or reports why it isn't ready yet.
+    /// Enforces the advertised polling `interval` server-side: a client polling too fast is
+    /// told to slow down rather than risking the device code being locked out as abuse.
+    async fn poll_device_token(
+        &self,
+        device_code: &str,
+    ) -> std::result::Result<TokenAuthResponse, DevicePollError> {
+        let token = self
+            .db
+            .get_device_token(device_code)
+            .await
+            .map_err(|e| DevicePollError::Other(e.into()))?
+            .ok_or(DevicePollError::ExpiredToken)?;
+
+        if token.is_expired() {
+            return Err(DevicePollError::ExpiredToken);
+        }
+        if let Some(last_polled_at) = token.last_polled_at {
+            if Utc::now().signed_duration_since(*last_polled_at) < DEVICE_CODE_POLL_INTERVAL {
+                return Err(DevicePollError::SlowDown);
+            }
+        }
+        self.db
+            .mark_device_token_polled(device_code)
+            .await
+            .map_err(|e| DevicePollError::Other(e.into()))?;
+
+        let Some(user_id) = token.user_id else {
+            return Err(DevicePollError::AuthorizationPending);
+        };
+        let user = self
+            .db
+            .get_user(user_id)
+            .await
+            .map_err(|e| DevicePollError::Other(e.into()))?
+            .ok_or_else(|| DevicePollError::Other(anyhow!("User not found")))?;
+
+        self.db
+            .delete_device_token(device_code)
+            .await
+            .map_err(|e| DevicePollError::Other(e.into()))?;
+
+        let refresh_token = generate_refresh_token();
+        self.db
+            .create_refresh_token(user.id, &refresh_token)
+            .await
+            .map_err(|e| DevicePollError::Other(e.into()))?;
+        let access_token = generate_jwt(JWTPayload::new(user.email.clone(), user.is_admin))
+            .map_err(|_| DevicePollError::Other(anyhow!("Unknown error")))?;
+
+        Ok(TokenAuthResponse::new(access_token, refresh_token))
+    }
+}
+
+const DEVICE_CODE_TTL: Duration = Duration::minutes(15);
+const DEVICE_CODE_POLL_INTERVAL: Duration = Duration::seconds(5);
+
+pub struct DeviceAuthorizationResponse {
+    pub device_code: String,
+    pub user_code: String,
+    pub verification_uri: String,
+    pub expires_in: i64,
+    pub interval: i64,
+}
+
+#[derive(Debug)]
+pub enum DevicePollError {
+    AuthorizationPending,
+    SlowDown,
+    ExpiredToken,
+    Other(anyhow::Error),
+}
+
+/// An 8-character, Crockford base32-ish code (digits and uppercase letters, vowel-light to
+/// avoid accidentally spelling words) that's easy for a user to type from one device to another.
+fn generate_user_code() -> String {
+    const ALPHABET: &[u8] = b"BCDFGHJKLMNPQRSTVWXZ0123456789";
+    let mut bytes = [0u8; 8];
+    OsRng.fill_bytes(&mut bytes);
+    bytes
+        .iter()
+        .map(|b| ALPHABET[*b as usize % ALPHABET.len()] as char)
+        .collect()
+}
+
+/// Payload of the short-lived token handed back by `token_auth` when a user has TOTP 2FA
+/// enabled. Distinct from [JWTPayload] and signed with its own key (see
+/// [generate_totp_challenge_jwt]/[validate_totp_challenge_jwt]) so a 2FA challenge can never
+/// be mistaken for, or replayed as, a real access token.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct Totp2FAChallengePayload {
+    sub: String,
+    purpose: &'static str,
+    exp: i64,
+}
+
+impl Totp2FAChallengePayload {
+    const PURPOSE: &'static str = "2fa_challenge";
+
+    fn new(email: String) -> Self {
+        Self {
+            sub: email,
+            purpose: Self::PURPOSE,
+            exp: (Utc::now() + Duration::minutes(5)).timestamp(),
+        }
+    }
+}
+
+/// Domain-separation label mixed into [jwt_token_secret] to derive
+/// [totp_challenge_signing_key], so the derived key can't be confused with (or substituted
+/// for) the access-token signing key even though both trace back to the same secret.
+const TOTP_CHALLENGE_KEY_LABEL: &[u8] = b"tabby-totp-2fa-challenge-v1";
+
+/// Signing key for 2FA challenge tokens: an HMAC of the same configured secret
+/// `generate_jwt`/`validate_jwt` sign access tokens with, so every replica behind a load
+/// balancer derives the same key, domain-separated from the access-token key by
+/// [TOTP_CHALLENGE_KEY_LABEL] so one can't be substituted for the other.
+fn totp_challenge_signing_key() -> Vec<u8> {
+    let mut mac = Hmac::<Sha1>::new_from_slice(jwt_token_secret().as_bytes())
+        .expect("HMAC accepts any key length");
+    mac.update(TOTP_CHALLENGE_KEY_LABEL);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn generate_totp_challenge_jwt(key: &[u8], payload: Totp2FAChallengePayload) -> Result<String> {
+    jsonwebtoken::encode(
+        &jsonwebtoken::Header::new(jsonwebtoken::Algorithm::HS256),
+        &payload,
+        &jsonwebtoken::EncodingKey::from_secret(key),
+    )
+    .map_err(|e| anyhow!("Failed to sign 2FA challenge: {e}").into())
+}
+
+fn validate_totp_challenge_jwt(key: &[u8], token: &str) -> Result<Totp2FAChallengePayload> {
+    let mut validation = jsonwebtoken::Validation::new(jsonwebtoken::Algorithm::HS256);
+    validation.set_required_spec_claims(&["exp"]);
+    let data = jsonwebtoken::decode::<Totp2FAChallengePayload>(
+        token,
+        &jsonwebtoken::DecodingKey::from_secret(key),
+        &validation,
+    )
+    .map_err(|e| anyhow!("Invalid or expired 2FA challenge: {e}"))?;
+    Ok(data.claims)
+}
+
+const TOTP_STEP_SECONDS: u64 = 30;
+const TOTP_WINDOW_STEPS: i64 = 1;
+
+/// Computes the 6-digit RFC 6238 TOTP code for `secret` at time step `counter`.
+fn totp_code_at(secret: &[u8], counter: u64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC accepts any key length");
+    mac.update(&counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let truncated = ((hash[offset] as u32 & 0x7f) << 24)
+        | ((hash[offset + 1] as u32) << 16)
+        | ((hash[offset + 2] as u32) << 8)
+        | (hash[offset + 3] as u32);
+    truncated % 1_000_000
+}
+
+/// Verifies `code` against `secret` allowing ±1 time step of clock drift.
+fn totp_verify(secret: &[u8], code: &str) -> bool {
+    let Ok(code) = code.parse::<u32>() else {
+        return false;
+    };
+    let now_step = Utc::now().timestamp() as u64 / TOTP_STEP_SECONDS;
+    (-TOTP_WINDOW_STEPS..=TOTP_WINDOW_STEPS).any(|drift| {
+        let step = now_step as i64 + drift;
+        step >= 0 && totp_code_at(secret, step as u64) == code
+    })
+}
+
+fn generate_totp_secret_bytes() -> Vec<u8> {
+    let mut bytes = [0u8; 20];
+    OsRng.fill_bytes(&mut bytes);
+    bytes.to_vec()
+}
+
+fn generate_recovery_codes() -> Vec<String> {
+    (0..8)
+        .map(|_| {
+            let mut bytes = [0u8; 5];
+            OsRng.fill_bytes(&mut bytes);
+            base32::encode(base32::Alphabet::Rfc4648 { padding: false }, &bytes)
+        })
+        .collect()
+}
+
+impl AuthenticationServiceImpl {
+    /// Begins TOTP enrollment, returning an `otpauth://` URI for QR rendering. The secret is
+    /// not considered active until confirmed with a valid code via [Self::confirm_totp].
+    async fn generate_totp_secret(&self, user_id: i32) -> Result<String> {
+        let user = self.db.get_user(user_id).await?.context("User not found")?;
+        let secret = generate_totp_secret_bytes();
+        let encoded = base32::encode(base32::Alphabet::Rfc4648 { padding: false }, &secret);
+        self.db.create_pending_totp_credential(user_id, &secret).await?;
+
+        Ok(format!(
+            "otpauth://totp/Tabby:{email}?secret={secret}&issuer=Tabby",
+            email = user.email,
+            secret = encoded
+        ))
+    }
+
+    /// Confirms enrollment with a first valid code, activates the secret, and returns a set
+    /// of one-time recovery codes (stored hashed, like passwords) for lost-device recovery.
+    async fn confirm_totp(&self, user_id: i32, code: &str) -> Result<Vec<String>> {
+        let secret = self
+            .db
+            .get_pending_totp_credential(user_id)
+            .await?
+            .context("No pending TOTP enrollment")?;
+        if !totp_verify(&secret, code) {
+            return Err(anyhow!("TOTP code is not valid").into());
+        }
+
+        let recovery_codes = generate_recovery_codes();
+        let hashed: Vec<String> = recovery_codes
+            .iter()
+            .map(|c| password_hash(c))
+            .collect::<password_hash::Result<_>>()
+            .map_err(|_| anyhow!("Unknown error"))?;
+        self.db
+            .activate_totp_credential(user_id, &secret, &hashed)
+            .await?;
+        Ok(recovery_codes)
+    }
+
+    async fn disable_totp(&self, user_id: i32) -> Result<()> {
+        Ok(self.db.delete_totp_credential(user_id).await?)
+    }
+
+    /// Redeems a single-use recovery code outside of the login challenge flow, e.g. so a
+    /// signed-in user can confirm they still have one before disabling 2FA.
+    async fn consume_recovery_code(&self, user_id: i32, code: &str) -> Result<bool> {
+        Ok(self.db.consume_recovery_code(user_id, code).await?)
+    }
+
+    /// Redeems a 2FA challenge token from `token_auth` with a TOTP code (or a recovery code),
+    /// rejecting the same code twice within one 30-second step to prevent replay, and locking
+    /// out the account (the same way [Self::token_auth] does for passwords) after repeated
+    /// wrong codes so the 6-digit space can't be brute forced.
+    async fn verify_totp(&self, challenge_token: &str, code: &str) -> Result<TokenAuthResponse> {
+        let claims = validate_totp_challenge_jwt(&totp_challenge_signing_key(), challenge_token)
+            .map_err(|_| anyhow!("Invalid or expired challenge"))?;
+        if claims.purpose != Totp2FAChallengePayload::PURPOSE {
+            return Err(anyhow!("Invalid or expired challenge").into());
+        }
+
+        let user = self
+            .db
+            .get_user_by_email(&claims.sub)
+            .await?
+            .context("User not found")?;
+
+        let limits = self.read_rate_limit_settings().await?;
+        let window = Duration::minutes(limits.lockout_window_minutes);
+        let failures = self.db.count_recent_failed_totp_attempts(user.id, window).await?;
+        if lockout_minutes(failures as u32, limits.lockout_threshold, limits.lockout_base_minutes) > 0 {
+            return Err(anyhow!("Too many attempts, please try again later").into());
+        }
+
+        let secret = self
+            .db
+            .get_totp_credential(user.id)
+            .await?
+            .context("TOTP is not enabled for this account")?;
+
+        let accepted = if totp_verify(&secret, code)
+            && !self.db.is_totp_step_consumed(user.id, code).await?
+        {
+            self.db.mark_totp_step_consumed(user.id, code).await?;
+            true
+        } else {
+            self.db.consume_recovery_code(user.id, code).await?
+        };
+        if !accepted {
+            self.db.record_failed_totp_attempt(user.id).await?;
+            return Err(anyhow!("TOTP code is not valid").into());
+        }
+        self.db.clear_failed_totp_attempts(user.id).await?;
+
+        let refresh_token = generate_refresh_token();
+        self.db
+            .create_refresh_token(user.id, &refresh_token)
+            .await?;
+        let access_token = generate_jwt(JWTPayload::new(user.email.clone(), user.is_admin))
+            .map_err(|_| anyhow!("Unknown error"))?;
+        Ok(TokenAuthResponse::new(access_token, refresh_token))
+    }
+
+    /// Sends a confirmation link to `new_email`, mirroring [Self::request_password_reset_email]:
+    /// the email isn't changed until [Self::confirm_email_change] validates the signed,
+    /// expiring token, so a typo or someone else's address never silently takes over the slot.
+    async fn request_email_change(&self, user_id: i32, new_email: String) -> Result<()> {
+        if self.db.get_user_by_email(&new_email).await?.is_some() {
+            return Err(anyhow!("Email is already registered").into());
+        }
+        let code = self
+            .db
+            .create_email_change(user_id, &new_email)
+            .await?;
+        self.mail.send_email_change_email(new_email, code).await?;
+        Ok(())
+    }
+
+    /// Validates the token from [Self::request_email_change] and atomically swaps the user's
+    /// email, but only after confirming it hasn't been claimed in the meantime.
+    async fn confirm_email_change(&self, code: &str) -> Result<()> {
+        let (user_id, new_email) = self.db.verify_email_change(code).await?;
+        self.db.delete_email_change_by_code(code).await?;
+        if self.db.get_user_by_email(&new_email).await?.is_some() {
+            return Err(anyhow!("Email is already registered").into());
+        }
+        self.db.update_user_email(user_id, &new_email).await?;
+        Ok(())
+    }
+
+    /// Soft-deletes the caller's own account after re-verifying `password`, refusing when
+    /// `user_id` is the sole owner so the instance can never lock itself out.
+    async fn delete_own_account(&self, user_id: i32, password: &str) -> Result<()> {
+        let user = self.db.get_user(user_id).await?.context("User not found")?;
+        if user.is_owner() {
+            return Err(anyhow!("The owner's account cannot be deleted").into());
+        }
+        if !password_verify(password, &user.password_encrypted) {
+            return Err(anyhow!("Password is not valid").into());
+        }
+        self.db.delete_user(user_id).await?;
+        Ok(())
+    }
+
+    /// Issues a new personal access token for `user_id`, returning the raw token exactly
+    /// once. Only its Argon2 hash is persisted -- the same hashing primitive used for login
+    /// passwords -- so a leaked database dump can't be used to recover or replay it.
+    async fn create_personal_access_token(
+        &self,
+        user_id: i32,
+        name: String,
+        scope: PersonalAccessTokenScope,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<String> {
+        let secret = generate_personal_access_token_secret();
+        let hash = password_hash(&secret).map_err(|e| anyhow!("Failed to hash token: {e}"))?;
+        let id = self
+            .db
+            .create_personal_access_token(user_id, &name, scope as i32, &hash, expires_at)
+            .await?;
+        Ok(format!("{PERSONAL_ACCESS_TOKEN_PREFIX}{id}.{secret}"))
+    }
+
+    /// Lists `user_id`'s tokens. Never returns the secret, only the metadata needed to let
+    /// a user recognize and manage what they've issued.
+    async fn list_personal_access_tokens(&self, user_id: i32) -> Result<Vec<PersonalAccessToken>> {
+        Ok(self
+            .db
+            .list_personal_access_tokens(user_id)
+            .await?
+            .into_iter()
+            .map(|t| PersonalAccessToken {
+                id: t.id.as_id(),
+                name: t.name,
+                scope: if t.scope == PersonalAccessTokenScope::FullAccess as i32 {
+                    PersonalAccessTokenScope::FullAccess
+                } else {
+                    PersonalAccessTokenScope::ReadOnly
+                },
+                created_at: *t.created_at,
+                last_used_at: t.last_used_at.map(|ts| *ts),
+                expires_at: t.expires_at.map(|ts| *ts),
+            })
+            .collect())
+    }
+
+    async fn revoke_personal_access_token(&self, id: &ID) -> Result<()> {
+        Ok(self.db.delete_personal_access_token(id.as_rowid()?).await?)
+    }
+
+    /// Authenticates a raw `tabby_pat_...` token, returning the owning user's id. The embedded
+    /// row id makes the lookup an indexed query; the secret half is checked with
+    /// [password_verify], the same constant-time comparison used for login.
+    async fn authenticate_pat(&self, raw_token: &str) -> Result<i32> {
+        let invalid = || anyhow!("Token is not valid").into();
+
+        let body = raw_token
+            .strip_prefix(PERSONAL_ACCESS_TOKEN_PREFIX)
+            .ok_or_else(invalid)?;
+        let (id, secret) = body.split_once('.').ok_or_else(invalid)?;
+        let id: i64 = id.parse().map_err(|_| invalid())?;
+
+        let token = self
+            .db
+            .get_personal_access_token(id)
+            .await?
+            .ok_or_else(invalid)?;
+
+        if token.expires_at.is_some_and(|exp| *exp < Utc::now()) {
+            return Err(invalid());
+        }
+        if !password_verify(secret, &token.hash) {
+            return Err(invalid());
+        }
+
+        self.db.update_personal_access_token_last_used_at(id).await?;
+        Ok(token.user_id)
+    }
+
+    /// Completes the OIDC authorization-code exchange and resolves the caller's email.
+    ///
+    /// Prefers the userinfo endpoint when the discovery document advertises one, since it
+    /// avoids an extra signature-verification round trip; falls back to validating the
+    /// returned `id_token` against the issuer's JWKS otherwise.
+    async fn fetch_oidc_user_email(&self, code: String) -> std::result::Result<String, OAuthError> {
+        let credential = self
+            .db
+            .read_oidc_credential()
+            .await
+            .map_err(|e| OAuthError::Other(e.into()))?
+            .ok_or(OAuthError::CredentialNotActive)?;
+
+        let discovery = self
+            .oidc_discovery
+            .get_or_fetch(&credential.issuer)
+            .await
+            .map_err(OAuthError::Other)?;
+
+        let redirect_uri = self.oauth_callback_url(OAuthProvider::Oidc).await?;
+
+        let http = reqwest::Client::new();
+        let token_response: OidcTokenResponse = http
+            .post(&discovery.token_endpoint)
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", &code),
+                ("redirect_uri", &redirect_uri),
+                ("client_id", &credential.client_id),
+                ("client_secret", &credential.client_secret),
+            ])
+            .send()
+            .await
+            .map_err(|e| OAuthError::Other(e.into()))?
+            .json()
+            .await
+            .map_err(|e| OAuthError::Other(e.into()))?;
+
+        if let Some(userinfo_endpoint) = &discovery.userinfo_endpoint {
+            let userinfo: OidcUserInfo = http
+                .get(userinfo_endpoint)
+                .bearer_auth(&token_response.access_token)
+                .send()
+                .await
+                .map_err(|e| OAuthError::Other(e.into()))?
+                .json()
+                .await
+                .map_err(|e| OAuthError::Other(e.into()))?;
+            if !userinfo.email_verified.unwrap_or(false) {
+                return Err(OAuthError::Other(anyhow!("Email is not verified")));
+            }
+            return Ok(userinfo.email);
+        }
+
+        let id_token = token_response
+            .id_token
+            .ok_or_else(|| OAuthError::Other(anyhow!("Provider returned no id_token")))?;
+        let claims = verify_oidc_id_token(
+            &id_token,
+            &discovery.jwks_uri,
+            &credential.issuer,
+            &credential.client_id,
+        )
+        .await
+        .map_err(OAuthError::Other)?;
+        if !claims.email_verified.unwrap_or(false) {
+            return Err(OAuthError::Other(anyhow!("Email is not verified")));
+        }
+        Ok(claims.email)
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct OidcTokenResponse {
+    access_token: String,
+    id_token: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct OidcUserInfo {
+    email: String,
+    email_verified: Option<bool>,
+}
+
+#[derive(serde::Deserialize)]
+struct OidcIdTokenClaims {
+    email: String,
+    email_verified: Option<bool>,
+}
+
+async fn verify_oidc_id_token(
+    id_token: &str,
+    jwks_uri: &str,
+    issuer: &str,
+    client_id: &str,
+) -> Result<OidcIdTokenClaims> {
+    let jwks: jsonwebtoken::jwk::JwkSet = reqwest::get(jwks_uri)
+        .await
+        .map_err(|e| anyhow!("Failed to fetch JWKS: {e}"))?
+        .json()
+        .await
+        .map_err(|e| anyhow!("Malformed JWKS: {e}"))?;
+
+    let header = jsonwebtoken::decode_header(id_token)?;
+    if header.alg != jsonwebtoken::Algorithm::RS256 {
+        return Err(anyhow!("Unsupported id_token signing algorithm {:?}", header.alg).into());
+    }
+    let kid = header
+        .kid
+        .ok_or_else(|| anyhow!("id_token is missing a `kid` header"))?;
+    let jwk = jwks
+        .find(&kid)
+        .ok_or_else(|| anyhow!("No matching JWK for kid {kid}"))?;
+    let decoding_key = jsonwebtoken::DecodingKey::from_jwk(jwk)?;
+
+    let mut validation = jsonwebtoken::Validation::new(jsonwebtoken::Algorithm::RS256);
+    validation.validate_exp = true;
+    validation.set_audience(&[client_id]);
+    validation.set_issuer(&[issuer]);
+    let claims = jsonwebtoken::decode::<OidcIdTokenClaims>(id_token, &decoding_key, &validation)?
+        .claims;
+    Ok(claims)
+}
+
 async fn get_or_create_oauth_user(db: &DbConn, email: &str) -> Result<(i32, bool), OAuthError> {
     if let Some(user) = db.get_user_by_email(email).await? {
         return user
@@ -411,11 +1409,13 @@ async fn get_or_create_oauth_user(db: &DbConn, email: &str) -> Result<(i32, bool
         // 1. both `register` & `token_auth` mutation will do input validation, so empty password won't be accepted
         // 2. `password_verify` will always return false for empty password hash read from user table
         // so user created here is only able to login by github oauth, normal login won't work
-        Ok((
-            db.create_user(email.to_owned(), "".to_owned(), false)
-                .await?,
-            false,
-        ))
+        let id = db
+            .create_user(email.to_owned(), "".to_owned(), false)
+            .await?;
+        // The IdP (or LDAP directory) already proved ownership of this email, so there's no
+        // self-signup verification step to gate on.
+        db.mark_user_verified(id).await?;
+        Ok((id, false))
     } else {
         let Some(invitation) = db.get_invitation_by_email(email).await.ok().flatten() else {
             return Err(OAuthError::UserNotInvited);
@@ -424,6 +1424,7 @@ async fn get_or_create_oauth_user(db: &DbConn, email: &str) -> Result<(i32, bool
         let id = db
             .create_user_with_invitation(email.to_owned(), "".to_owned(), false, invitation.id)
             .await?;
+        db.mark_user_verified(id).await?;
         let user = db.get_user(id).await?.unwrap();
         Ok((user.id, user.is_admin))
     }
@@ -456,21 +1457,162 @@ async fn check_invitation(
     Ok(Some(invitation))
 }
 
-fn password_hash(raw: &str) -> password_hash::Result<String> {
-    let salt = SaltString::generate(&mut OsRng);
-    let argon2 = Argon2::default();
-    let hash = argon2.hash_password(raw.as_bytes(), &salt)?.to_string();
+/// Argon2id cost parameters, tunable per deployment via [SettingService] so memory/iteration/
+/// parallelism cost can match the host hardware. [Argon2Params::default] reproduces the
+/// library's prior hardcoded behavior.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Argon2Params {
+    pub m_cost: u32,
+    pub t_cost: u32,
+    pub p_cost: u32,
+}
+
+impl Default for Argon2Params {
+    fn default() -> Self {
+        let defaults = argon2::Params::default();
+        Self {
+            m_cost: defaults.m_cost(),
+            t_cost: defaults.t_cost(),
+            p_cost: defaults.p_cost(),
+        }
+    }
+}
+
+impl Argon2Params {
+    /// Bounds chosen to keep a single login from being able to exhaust server memory/CPU
+    /// while still allowing real hardening (e.g. OWASP's 19 MiB / t=2 baseline, or much higher
+    /// on beefier hardware).
+    fn validate(&self) -> Result<()> {
+        if !(8..=2_097_152).contains(&self.m_cost) {
+            return Err(anyhow!("Argon2 m_cost must be between 8 and 2097152 KiB").into());
+        }
+        if !(1..=10).contains(&self.t_cost) {
+            return Err(anyhow!("Argon2 t_cost must be between 1 and 10").into());
+        }
+        if !(1..=16).contains(&self.p_cost) {
+            return Err(anyhow!("Argon2 p_cost must be between 1 and 16").into());
+        }
+        Ok(())
+    }
+
+    fn to_argon2(self) -> Argon2<'static> {
+        let params = argon2::Params::new(self.m_cost, self.t_cost, self.p_cost, None)
+            .expect("bounds were validated on write");
+        Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
+    }
+}
+
+fn password_hash(raw: &str) -> password_hash::Result<String> {
+    password_hash_with_params(raw, Argon2Params::default())
+}
+
+fn password_hash_with_params(raw: &str, params: Argon2Params) -> password_hash::Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = params
+        .to_argon2()
+        .hash_password(raw.as_bytes(), &salt)?
+        .to_string();
+
+    Ok(hash)
+}
+
+/// Verifies `raw` against `hash`, transparently supporting bcrypt hashes (`$2a$`/`$2b$`/`$2y$`)
+/// inherited from systems migrated onto Tabby. Argon2 remains the only scheme ever produced by
+/// [password_hash]; bcrypt support exists purely to read legacy data.
+///
+/// Note: Argon2's `verify_password` reads the cost parameters embedded in `hash` itself, so
+/// this keeps working for hashes produced under an older [Argon2Params] configuration.
+fn password_verify(raw: &str, hash: &str) -> bool {
+    if is_bcrypt_hash(hash) {
+        bcrypt::verify(raw, hash).unwrap_or(false)
+    } else if let Ok(parsed_hash) = argon2::PasswordHash::new(hash) {
+        let argon2 = Argon2::default();
+        argon2.verify_password(raw.as_bytes(), &parsed_hash).is_ok()
+    } else {
+        false
+    }
+}
+
+fn is_bcrypt_hash(hash: &str) -> bool {
+    hash.starts_with("$2a$") || hash.starts_with("$2b$") || hash.starts_with("$2y$")
+}
+
+/// Outcome of [verify_password], carrying a `NeedsRehash` signal so callers can upgrade a
+/// password's storage (legacy bcrypt, or Argon2 under stale cost parameters) the moment they
+/// have the plaintext in hand, without a separate comparison pass.
+#[derive(Debug, PartialEq, Eq)]
+enum PasswordVerifyOutcome {
+    Invalid,
+    Valid,
+    ValidNeedsRehash,
+}
+
+/// Constant-time (with respect to `raw`) password verification against a stored PHC hash,
+/// reporting whether the hash should be upgraded to the currently configured [Argon2Params].
+fn verify_password(raw: &str, hash: &str, target: Argon2Params) -> PasswordVerifyOutcome {
+    if !password_verify(raw, hash) {
+        return PasswordVerifyOutcome::Invalid;
+    }
+    if is_bcrypt_hash(hash) || argon2_hash_needs_rehash(hash, target) {
+        PasswordVerifyOutcome::ValidNeedsRehash
+    } else {
+        PasswordVerifyOutcome::Valid
+    }
+}
+
+/// True when `hash`'s embedded Argon2 parameters don't match `target`, meaning it should be
+/// re-hashed and persisted the next time the plaintext is available (i.e. on successful login).
+fn argon2_hash_needs_rehash(hash: &str, target: Argon2Params) -> bool {
+    let Ok(parsed) = argon2::PasswordHash::new(hash) else {
+        return false;
+    };
+    let Ok(current) = argon2::Params::try_from(&parsed) else {
+        return false;
+    };
+    current.m_cost() != target.m_cost
+        || current.t_cost() != target.t_cost
+        || current.p_cost() != target.p_cost
+}
+
+/// Thresholds governing password-reset-request throttling and login/reset-code lockout,
+/// tunable per deployment via [SettingService] next to [Argon2Params].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RateLimitSettings {
+    /// How many password reset emails a single email/IP may request within
+    /// `reset_window_minutes` before further requests are silently dropped.
+    pub reset_max_attempts: u32,
+    pub reset_window_minutes: i64,
+    /// How many failures (logins, or reset-code attempts) an identity may accrue within
+    /// `lockout_window_minutes` before [lockout_minutes] starts returning a nonzero delay.
+    pub lockout_threshold: u32,
+    pub lockout_window_minutes: i64,
+    /// Lockout duration applied at exactly `lockout_threshold` failures; it doubles with
+    /// every failure beyond that.
+    pub lockout_base_minutes: i64,
+}
 
-    Ok(hash)
+impl Default for RateLimitSettings {
+    fn default() -> Self {
+        Self {
+            reset_max_attempts: 3,
+            reset_window_minutes: 60,
+            lockout_threshold: 5,
+            lockout_window_minutes: 15,
+            lockout_base_minutes: 1,
+        }
+    }
 }
 
-fn password_verify(raw: &str, hash: &str) -> bool {
-    if let Ok(parsed_hash) = argon2::PasswordHash::new(hash) {
-        let argon2 = Argon2::default();
-        argon2.verify_password(raw.as_bytes(), &parsed_hash).is_ok()
-    } else {
-        false
+/// Minutes an identity with `failures` recent failures should be locked out, doubling with
+/// each failure past `threshold` and returning `0` (no lockout) below it. The exponent is
+/// capped so a determined attacker can't turn an unbounded failure count into an effectively
+/// permanent lockout.
+fn lockout_minutes(failures: u32, threshold: u32, base_minutes: i64) -> i64 {
+    if failures < threshold {
+        return 0;
     }
+    let exponent = (failures - threshold).min(10);
+    base_minutes * (1i64 << exponent)
 }
 
 #[cfg(test)]
@@ -559,7 +1701,12 @@ mod tests {
 
     async fn register_admin_user(service: &AuthenticationServiceImpl) -> RegisterResponse {
         service
-            .register(ADMIN_EMAIL.to_owned(), ADMIN_PASSWORD.to_owned(), None)
+            .register(
+                ADMIN_EMAIL.to_owned(),
+                ADMIN_PASSWORD.to_owned(),
+                None,
+                DeviceContext::default(),
+            )
             .await
             .unwrap()
     }
@@ -569,7 +1716,11 @@ mod tests {
         let service = test_authentication_service().await;
         assert_matches!(
             service
-                .token_auth(ADMIN_EMAIL.to_owned(), "12345678".to_owned())
+                .token_auth(
+                    ADMIN_EMAIL.to_owned(),
+                    "12345678".to_owned(),
+                    DeviceContext::default(),
+                )
                 .await,
             Err(_)
         );
@@ -578,23 +1729,173 @@ mod tests {
 
         assert_matches!(
             service
-                .token_auth(ADMIN_EMAIL.to_owned(), "12345678".to_owned())
+                .token_auth(
+                    ADMIN_EMAIL.to_owned(),
+                    "12345678".to_owned(),
+                    DeviceContext::default(),
+                )
                 .await,
             Err(_)
         );
 
         let resp1 = service
-            .token_auth(ADMIN_EMAIL.to_owned(), ADMIN_PASSWORD.to_owned())
+            .token_auth(
+                ADMIN_EMAIL.to_owned(),
+                ADMIN_PASSWORD.to_owned(),
+                DeviceContext::default(),
+            )
             .await
             .unwrap();
         let resp2 = service
-            .token_auth(ADMIN_EMAIL.to_owned(), ADMIN_PASSWORD.to_owned())
+            .token_auth(
+                ADMIN_EMAIL.to_owned(),
+                ADMIN_PASSWORD.to_owned(),
+                DeviceContext::default(),
+            )
             .await
             .unwrap();
         // each auth should generate a new refresh token
         assert_ne!(resp1.refresh_token, resp2.refresh_token);
     }
 
+    #[tokio::test]
+    async fn test_bcrypt_hash_is_upgraded_on_login() {
+        let service = test_authentication_service().await;
+        let password = "12345678dD^";
+        let legacy_hash = bcrypt::hash(password, bcrypt::DEFAULT_COST).unwrap();
+        let id = service
+            .db
+            .create_user("bcrypt@example.com".into(), legacy_hash, true)
+            .await
+            .unwrap();
+        service.db.mark_user_verified(id).await.unwrap();
+
+        service
+            .token_auth(
+                "bcrypt@example.com".into(),
+                password.into(),
+                DeviceContext::default(),
+            )
+            .await
+            .unwrap();
+
+        let user = service
+            .db
+            .get_user_by_email("bcrypt@example.com")
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(user.password_encrypted.starts_with("$argon2id$"));
+    }
+
+    #[test]
+    fn test_totp_code_at_rfc6238_vector() {
+        // RFC 6238 Appendix B, T=59s (counter 1) over the 20-byte ASCII SHA1 test secret.
+        let secret = b"12345678901234567890";
+        assert_eq!(totp_code_at(secret, 1), 287_082);
+    }
+
+    #[test]
+    fn test_verify_password_outcomes() {
+        let raw = "12345678dD^";
+        let fresh_hash = password_hash(raw).unwrap();
+        assert_eq!(
+            verify_password(raw, &fresh_hash, Argon2Params::default()),
+            PasswordVerifyOutcome::Valid
+        );
+        assert_eq!(
+            verify_password("wrong", &fresh_hash, Argon2Params::default()),
+            PasswordVerifyOutcome::Invalid
+        );
+
+        let weak_hash = password_hash_with_params(
+            raw,
+            Argon2Params {
+                m_cost: 8,
+                t_cost: 1,
+                p_cost: 1,
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            verify_password(raw, &weak_hash, Argon2Params::default()),
+            PasswordVerifyOutcome::ValidNeedsRehash
+        );
+
+        let legacy_hash = bcrypt::hash(raw, bcrypt::DEFAULT_COST).unwrap();
+        assert_eq!(
+            verify_password(raw, &legacy_hash, Argon2Params::default()),
+            PasswordVerifyOutcome::ValidNeedsRehash
+        );
+    }
+
+    #[test]
+    fn test_argon2_params_validate() {
+        assert!(Argon2Params::default().validate().is_ok());
+        assert!(Argon2Params {
+            m_cost: 1,
+            t_cost: 2,
+            p_cost: 1
+        }
+        .validate()
+        .is_err());
+        assert!(Argon2Params {
+            m_cost: 19456,
+            t_cost: 0,
+            p_cost: 1
+        }
+        .validate()
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_password_is_upgraded_when_argon2_params_change() {
+        let service = test_authentication_service().await;
+        register_admin_user(&service).await;
+
+        let weaker = Argon2Params {
+            m_cost: 8,
+            t_cost: 1,
+            p_cost: 1,
+        };
+        let weak_hash = password_hash_with_params(ADMIN_PASSWORD, weaker).unwrap();
+        service
+            .db
+            .update_user_password(
+                service
+                    .get_user_by_email(ADMIN_EMAIL)
+                    .await
+                    .unwrap()
+                    .id
+                    .as_rowid()
+                    .unwrap(),
+                weak_hash,
+            )
+            .await
+            .unwrap();
+
+        service
+            .token_auth(
+                ADMIN_EMAIL.to_owned(),
+                ADMIN_PASSWORD.to_owned(),
+                DeviceContext::default(),
+            )
+            .await
+            .unwrap();
+
+        let user = service.get_user_by_email(ADMIN_EMAIL).await.unwrap();
+        assert!(!argon2_hash_needs_rehash(
+            &service
+                .db
+                .get_user_by_email(&user.email)
+                .await
+                .unwrap()
+                .unwrap()
+                .password_encrypted,
+            Argon2Params::default()
+        ));
+    }
+
     #[tokio::test]
     async fn test_invitation_flow() {
         let service = test_authentication_service().await;
@@ -614,7 +1915,12 @@ mod tests {
         // Admin initialized, registeration requires a invitation code;
         assert_matches!(
             service
-                .register(email.to_owned(), password.to_owned(), None)
+                .register(
+                    email.to_owned(),
+                    password.to_owned(),
+                    None,
+                    DeviceContext::default(),
+                )
                 .await,
             Err(_)
         );
@@ -625,7 +1931,8 @@ mod tests {
                 .register(
                     email.to_owned(),
                     password.to_owned(),
-                    Some("abc".to_owned())
+                    Some("abc".to_owned()),
+                    DeviceContext::default(),
                 )
                 .await,
             Err(_)
@@ -637,6 +1944,7 @@ mod tests {
                 email.to_owned(),
                 password.to_owned(),
                 Some(invitation.code.clone()),
+                DeviceContext::default(),
             )
             .await
             .is_ok());
@@ -647,7 +1955,8 @@ mod tests {
                 .register(
                     email.to_owned(),
                     password.to_owned(),
-                    Some(invitation.code.clone())
+                    Some(invitation.code.clone()),
+                    DeviceContext::default(),
                 )
                 .await,
             Err(_)
@@ -817,6 +2126,96 @@ mod tests {
             .is_err());
     }
 
+    #[tokio::test]
+    async fn test_delete_own_account() {
+        let service = test_authentication_service().await;
+        let owner_id = service
+            .db
+            .create_user("owner@example.com".into(), "".into(), true)
+            .await
+            .unwrap();
+        let password = "12345678dD^";
+        let member_id = service
+            .db
+            .create_user(
+                "member@example.com".into(),
+                password_hash(password).unwrap(),
+                false,
+            )
+            .await
+            .unwrap();
+
+        // The owner can never delete themselves, even with the (empty) stored password.
+        assert!(service.delete_own_account(owner_id, "").await.is_err());
+
+        assert!(service.delete_own_account(member_id, "wrong").await.is_err());
+        assert!(service.delete_own_account(member_id, password).await.is_ok());
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_email_change_flow() {
+        let (service, smtp) = test_authentication_service_with_mail().await;
+        let user_id = service
+            .db
+            .create_user("old@example.com".into(), "".into(), false)
+            .await
+            .unwrap();
+
+        service
+            .request_email_change(user_id, "new@example.com".into())
+            .await
+            .unwrap();
+        assert!(smtp.list_mail().await[0]
+            .subject
+            .to_lowercase()
+            .contains("email"));
+
+        let change = service
+            .db
+            .get_email_change_by_user_id(user_id as i64)
+            .await
+            .unwrap()
+            .unwrap();
+        service.confirm_email_change(&change.code).await.unwrap();
+
+        let user = service.db.get_user(user_id).await.unwrap().unwrap();
+        assert_eq!(user.email, "new@example.com");
+    }
+
+    #[tokio::test]
+    async fn test_personal_access_token() {
+        let service = test_authentication_service().await;
+        let user_id = service
+            .db
+            .create_user("pat@example.com".into(), "".into(), false)
+            .await
+            .unwrap();
+
+        let token = service
+            .create_personal_access_token(
+                user_id,
+                "ci".into(),
+                PersonalAccessTokenScope::ReadOnly,
+                None,
+            )
+            .await
+            .unwrap();
+
+        let authenticated = service.authenticate_pat(&token).await.unwrap();
+        assert_eq!(authenticated, user_id);
+
+        let tokens = service.list_personal_access_tokens(user_id).await.unwrap();
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].name, "ci");
+        assert_eq!(tokens[0].scope, PersonalAccessTokenScope::ReadOnly);
+
+        service.revoke_personal_access_token(&tokens[0].id).await.unwrap();
+        assert!(service.authenticate_pat(&token).await.is_err());
+
+        assert!(service.authenticate_pat("tabby_pat_not-a-token").await.is_err());
+    }
+
     #[tokio::test]
     #[serial]
     async fn test_password_reset() {
@@ -831,7 +2230,7 @@ mod tests {
         let user = service.get_user_by_email("user@example.com").await.unwrap();
 
         let handle = service
-            .request_password_reset_email("user@example.com".into())
+            .request_password_reset_email("user@example.com".into(), None)
             .await
             .unwrap();
         handle.unwrap().await.unwrap();
@@ -847,8 +2246,8 @@ mod tests {
             .unwrap()
             .unwrap();
 
-        assert!(service.password_reset("", "newpass").await.is_err());
-        assert!(service.password_reset(&reset.code, "newpass").await.is_ok());
+        assert!(service.password_reset("", "newpass", None).await.is_err());
+        assert!(service.password_reset(&reset.code, "newpass", None).await.is_ok());
 
         // Test second reset, ensure expired code fails
         let user = service
@@ -860,7 +2259,7 @@ mod tests {
         assert_ne!(user.password_encrypted, "pass");
 
         service
-            .request_password_reset_email("user@example.com".into())
+            .request_password_reset_email("user@example.com".into(), None)
             .await
             .unwrap();
         let reset = service
@@ -877,7 +2276,7 @@ mod tests {
             .unwrap();
 
         assert!(service
-            .password_reset(&reset.code, "newpass2")
+            .password_reset(&reset.code, "newpass2", None)
             .await
             .is_err());
 
@@ -889,7 +2288,7 @@ mod tests {
             .unwrap();
 
         service
-            .request_password_reset_email("user2@example.com".into())
+            .request_password_reset_email("user2@example.com".into(), None)
             .await
             .unwrap();
         let reset = service
@@ -906,7 +2305,7 @@ mod tests {
             .unwrap();
 
         assert!(service
-            .password_reset(&reset.code, "newpass")
+            .password_reset(&reset.code, "newpass", None)
             .await
             .is_err());
 
@@ -924,6 +2323,231 @@ mod tests {
             .is_none());
     }
 
+    #[test]
+    fn test_lockout_minutes() {
+        assert_eq!(lockout_minutes(0, 5, 1), 0);
+        assert_eq!(lockout_minutes(4, 5, 1), 0);
+        assert_eq!(lockout_minutes(5, 5, 1), 1);
+        assert_eq!(lockout_minutes(6, 5, 1), 2);
+        assert_eq!(lockout_minutes(7, 5, 1), 4);
+        // The exponent is capped so failures can't grow the lockout unboundedly.
+        assert_eq!(lockout_minutes(5 + 20, 5, 1), 1 << 10);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_password_reset_request_is_throttled() {
+        let (service, smtp) = test_authentication_service_with_mail().await;
+        service
+            .db
+            .create_user("user@example.com".into(), "pass".into(), true)
+            .await
+            .unwrap();
+
+        let mut settings = service.read_rate_limit_settings().await.unwrap();
+        settings.reset_max_attempts = 2;
+        service.update_rate_limit_settings(settings).await.unwrap();
+
+        assert!(service
+            .request_password_reset_email("user@example.com".into(), None)
+            .await
+            .unwrap()
+            .is_some());
+        assert!(service
+            .request_password_reset_email("user@example.com".into(), None)
+            .await
+            .unwrap()
+            .is_some());
+        // Over the threshold: rejected, but still reported as success to avoid leaking state.
+        assert!(service
+            .request_password_reset_email("user@example.com".into(), None)
+            .await
+            .unwrap()
+            .is_none());
+        assert_eq!(smtp.list_mail().await.len(), 2);
+
+        // A nonexistent account is indistinguishable from a throttled one.
+        assert!(service
+            .request_password_reset_email("nobody@example.com".into(), None)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_login_lockout_after_repeated_failures() {
+        let service = test_authentication_service().await;
+        let password = "12345678dD^";
+        let id = service
+            .db
+            .create_user(
+                "lockout@example.com".into(),
+                password_hash(password).unwrap(),
+                true,
+            )
+            .await
+            .unwrap();
+        service.db.mark_user_verified(id).await.unwrap();
+
+        let mut settings = service.read_rate_limit_settings().await.unwrap();
+        settings.lockout_threshold = 2;
+        service.update_rate_limit_settings(settings).await.unwrap();
+
+        for _ in 0..2 {
+            assert!(service
+                .token_auth(
+                    "lockout@example.com".into(),
+                    "wrong".into(),
+                    DeviceContext::default(),
+                )
+                .await
+                .is_err());
+        }
+
+        // The correct password no longer helps once the account is locked out.
+        assert!(service
+            .token_auth(
+                "lockout@example.com".into(),
+                password.into(),
+                DeviceContext::default(),
+            )
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_email_verification() {
+        let (service, smtp) = test_authentication_service_with_mail().await;
+
+        // A user created outside the invitation/OAuth/bootstrap-admin paths starts unverified.
+        let email = "user@example.com";
+        let password_encrypted = password_hash("12345678dD^").unwrap();
+        let id = service
+            .db
+            .create_user(email.to_owned(), password_encrypted, false)
+            .await
+            .unwrap();
+
+        assert_matches!(
+            service
+                .token_auth(
+                    email.to_owned(),
+                    "12345678dD^".to_owned(),
+                    DeviceContext::default(),
+                )
+                .await,
+            Err(CoreError::EmailNotVerified)
+        );
+
+        service
+            .resend_verification_email(email.to_owned())
+            .await
+            .unwrap();
+        assert!(smtp.list_mail().await[0]
+            .subject
+            .to_lowercase()
+            .contains("verif"));
+
+        // Repeating the request inside the throttle window is rejected.
+        assert!(service
+            .resend_verification_email(email.to_owned())
+            .await
+            .is_err());
+
+        let verification = service
+            .db
+            .get_email_verification_by_user_id(id as i64)
+            .await
+            .unwrap()
+            .unwrap();
+        service.verify_email(&verification.code).await.unwrap();
+
+        assert!(service
+            .token_auth(
+                email.to_owned(),
+                "12345678dD^".to_owned(),
+                DeviceContext::default(),
+            )
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_totp_login_flow() {
+        let service = test_authentication_service().await;
+        register_admin_user(&service).await;
+        let user = service.get_user_by_email(ADMIN_EMAIL).await.unwrap();
+        let user_id = user.id.as_rowid().unwrap();
+
+        service.generate_totp_secret(user_id).await.unwrap();
+        let secret = service
+            .db
+            .get_pending_totp_credential(user_id)
+            .await
+            .unwrap()
+            .unwrap();
+        let code = totp_code_at(&secret, Utc::now().timestamp() as u64 / TOTP_STEP_SECONDS);
+        let recovery_codes = service
+            .confirm_totp(user_id, &format!("{code:06}"))
+            .await
+            .unwrap();
+        assert_eq!(recovery_codes.len(), 8);
+
+        // A correct password alone no longer completes the login.
+        fn expect_challenge(result: Result<TokenAuthResponse>) -> String {
+            match result {
+                Err(CoreError::TotpRequired(challenge)) => challenge,
+                other => panic!("expected a TOTP challenge, got {other:?}"),
+            }
+        }
+
+        let challenge = expect_challenge(
+            service
+                .token_auth(
+                    ADMIN_EMAIL.to_owned(),
+                    ADMIN_PASSWORD.to_owned(),
+                    DeviceContext::default(),
+                )
+                .await,
+        );
+
+        let totp_code = format!("{code:06}");
+        assert!(service.verify_totp(&challenge, &totp_code).await.is_ok());
+
+        // The same code cannot be replayed within the same step.
+        let challenge2 = expect_challenge(
+            service
+                .token_auth(
+                    ADMIN_EMAIL.to_owned(),
+                    ADMIN_PASSWORD.to_owned(),
+                    DeviceContext::default(),
+                )
+                .await,
+        );
+        assert!(service.verify_totp(&challenge2, &totp_code).await.is_err());
+
+        // A recovery code unblocks login when the authenticator is unavailable.
+        assert!(service
+            .verify_totp(&challenge2, &recovery_codes[0])
+            .await
+            .is_ok());
+        // Recovery codes are single-use.
+        let challenge3 = expect_challenge(
+            service
+                .token_auth(
+                    ADMIN_EMAIL.to_owned(),
+                    ADMIN_PASSWORD.to_owned(),
+                    DeviceContext::default(),
+                )
+                .await,
+        );
+        assert!(service
+            .verify_totp(&challenge3, &recovery_codes[0])
+            .await
+            .is_err());
+    }
+
     #[tokio::test]
     async fn test_pagination() {
         let service = test_authentication_service().await;